@@ -15,6 +15,7 @@
 #include <string>
 #include <iostream>
 #include <sstream>
+#include <iomanip>
 #include "nlohmann/json.hpp"
 #include "bandit/bandit.h"
 #include "btree/map.h"
@@ -22,6 +23,11 @@
 #include <map>
 #include <algorithm>
 #include <chrono>
+#include <functional>
+#include <cmath>
+#include <stdexcept>
+#include <optional>
+#include <limits>
 
 
 
@@ -39,9 +45,288 @@ const int ONE_DAY = 24 * 60 * 60;
 const int MINUTE = 60;
 const int FIVE_MINUTES = 5 * 60;
 const int ONE_HOUR = 60 * 60;
+const int FIVE_DAYS = 5 * ONE_DAY;
+const int TEN_MINUTES = 10 * 60;
 
 typedef std::pair<double, double> key_pair;
 
+// How a slot gets its value from the samples around it. Slots past
+// the last sample are NAN in every mode, so the k-th value is always
+// the slot k steps after start
+enum class sample_mode {
+	// the sample closest in time, which stairsteps between 3 hour
+	// samples. Slots before the first sample take its value
+	nearest,
+	// blend of the samples on either side, weighted by distance.
+	// NAN before the first sample as well. Recommended for minute
+	// slots, where it gives a smooth curve instead of a flat line
+	linear,
+	// average, highest or lowest of the samples inside the slot,
+	// NAN when the slot has none
+	mean,
+	max,
+	min,
+	// the given percentile, within [0, 100], of the samples inside the
+	// slot, blending the two nearest ranks. NAN when the slot has none
+	percentile,
+};
+
+
+// Throws if lat/lon aren't finite or fall outside [-90, 90] / [-180, 180],
+// so a bad location is caught before it ends up in a request URL
+
+void check_coordinates(double lat, double lon) {
+	if (!std::isfinite(lat) || lat < -90.0 || lat > 90.0) {
+		throw invalid_argument("latitude must be within [-90, 90]");
+	}
+	if (!std::isfinite(lon) || lon < -180.0 || lon > 180.0) {
+		throw invalid_argument("longitude must be within [-180, 180]");
+	}
+}
+
+
+// The server's dt_txt label for a dt, "YYYY-MM-DD hh:mm:ss" in UTC
+
+string format_dt_txt(int dt) {
+	time_t t = dt;
+	struct tm utc;
+	gmtime_r(&t, &utc);
+	char buf[20];
+	strftime(buf, sizeof(buf), "%Y-%m-%d %H:%M:%S", &utc);
+	return buf;
+}
+
+
+// Reads the (dt, temp) samples out of a five day forecast response.
+// Throws runtime_error with the server's message when cod isn't 200,
+// which OpenWeather sends as a string or a number depending on the error,
+// and when an element's dt_txt isn't format_dt_txt(dt), so the label can
+// be rebuilt from dt wherever it is needed
+
+vector<tuple<int, double>> parse_forecast(const string &body) {
+	auto parsed = json::parse(body);
+	if (parsed.contains("cod")) {
+		string cod = parsed["cod"].is_string() ? parsed["cod"].get<string>()
+						       : parsed["cod"].dump();
+		if (cod != "200") {
+			string message = parsed.contains("message") && parsed["message"].is_string()
+					 ? parsed["message"].get<string>() : "";
+			throw runtime_error("OpenWeather error " + cod + ": " + message);
+		}
+	}
+
+	unsigned response_count = parsed["cnt"]; // number of responses
+	vector<tuple<int, double>> data;
+	data.reserve(response_count);
+	for (auto &element : parsed["list"])
+	{
+		if (element.contains("dt_txt") && element["dt_txt"] != format_dt_txt(element["dt"])) {
+			throw runtime_error("dt_txt doesn't match dt " + element["dt"].dump());
+		}
+		data.push_back({element["dt"], element["main"]["temp"]});
+	}
+	return data;
+}
+
+
+// Slot size in seconds used for a requested range of the given length.
+// Thresholds are exclusive: a range of exactly two hours already gets
+// five minute slots, and exactly one day already gets hourly slots.
+
+int select_granularity(int requested_range) {
+	int granularity = ONE_HOUR;
+	if (requested_range < TWO_HOURS) {
+	    granularity = MINUTE;
+	} else if (requested_range < ONE_DAY) {
+	    granularity = FIVE_MINUTES;
+	}
+	return granularity;
+}
+
+
+// Throws unless granularity is one of the slot sizes
+// select_granularity can pick
+
+void check_granularity(int granularity) {
+	if (granularity != MINUTE && granularity != FIVE_MINUTES && granularity != ONE_HOUR) {
+		throw invalid_argument("granularity must be 60, 300 or 3600 seconds");
+	}
+}
+
+
+// Granularity used for a range: the one asked for if it isn't 0,
+// otherwise select_granularity's choice raised to min_granularity
+// (0 for no floor). A forced granularity must pass check_granularity.
+
+int resolve_granularity(int requested_range, int granularity, int min_granularity = 0) {
+	if (granularity == 0) {
+		return std::max(select_granularity(requested_range), min_granularity);
+	}
+	check_granularity(granularity);
+	return granularity;
+}
+
+
+// Throws unless start and the length of [start, end) are whole
+// multiples of granularity, which catches callers that build ranges
+// the slots can't line up with
+
+void check_alignment(int start, int end, int granularity) {
+	if (start % granularity != 0 || (end - start) % granularity != 0) {
+		throw invalid_argument("start and end must be aligned to the "
+				       + to_string(granularity) + " second granularity");
+	}
+}
+
+
+// Combines the samples with dt in [from, to) as mode asks for,
+// NAN when no sample falls inside
+
+double aggregate_slot(const btree::map<int, double> &data_map, int from, int to, sample_mode mode,
+		      double percentile = 50) {
+	auto first = data_map.lower_bound(from);
+	auto last = data_map.lower_bound(to);
+	if (first == last) {
+		return NAN;
+	}
+	if (mode == sample_mode::percentile) {
+		vector<double> values;
+		for (auto it = first; it != last; ++it) {
+			values.push_back(it->second);
+		}
+		std::sort(values.begin(), values.end());
+		double rank = percentile / 100 * (values.size() - 1);
+		size_t below = size_t(rank);
+		if (below + 1 >= values.size())
+			return values.back();
+		return values[below] + (rank - below) * (values[below + 1] - values[below]);
+	}
+	double result = first->second, sum = 0;
+	int count = 0;
+	for (auto it = first; it != last; ++it) {
+		sum += it->second;
+		count++;
+		if (mode == sample_mode::max)
+			result = std::max(result, it->second);
+		else if (mode == sample_mode::min)
+			result = std::min(result, it->second);
+	}
+	return mode == sample_mode::mean ? sum / count : result;
+}
+
+
+// Turns fetched (dt, temp) samples into one temperature per slot in
+// [start, end), filled as mode says. The slot size depends on the
+// length of the range unless granularity forces one (see
+// resolve_granularity). An empty range gives no slots, and one up to
+// a step long gives a single slot at start. No network access, so it
+// can be tested with hand built data.
+
+vector<double> sample_forecast(const vector<tuple<int, double>> &data, int start, int end,
+			       sample_mode mode = sample_mode::nearest, int granularity = 0,
+			       double percentile = 50) {
+	if (!(percentile >= 0 && percentile <= 100)) {
+		throw invalid_argument("percentile must be within [0, 100]");
+	}
+	// if the server repeats a dt, the samples for it are averaged
+	btree::map<int, double> data_map;
+	btree::map<int, int> counts;
+	for (auto &tup : data) {
+		data_map[get<0>(tup)] += get<1>(tup);
+		counts[get<0>(tup)]++;
+	}
+	for (auto &entry : data_map) {
+		entry.second /= counts[entry.first];
+	}
+	granularity = resolve_granularity(end - start, granularity);
+	bool aggregating = mode != sample_mode::nearest && mode != sample_mode::linear;
+	vector<double> ret;
+	for (int i = start; i < end; i += granularity) {
+		if (aggregating) {
+			ret.push_back(aggregate_slot(data_map, i, i + granularity, mode, percentile));
+			continue;
+		}
+		auto low = data_map.lower_bound(i);
+		if (low == data_map.end()) {
+			ret.push_back(NAN);
+		} else if (low->first == i) {
+			ret.push_back(low->second);
+		} else if (low == data_map.begin()) {
+			ret.push_back(mode == sample_mode::linear ? NAN : low->second);
+		} else if (mode == sample_mode::linear) {
+			auto prev = std::prev(low);
+			double weight = double(i - prev->first) / (low->first - prev->first);
+			ret.push_back(prev->second + weight * (low->second - prev->second));
+		} else {
+			auto prev = std::prev(low);
+			if ((i - prev->first) < (low->first - i))
+				ret.push_back(prev->second);
+			else
+				ret.push_back(low->second);
+		}
+	}
+	return ret;
+}
+
+
+// Units the clients' Kelvin temperatures can be converted to
+enum class temperature_unit {
+	kelvin,
+	celsius,
+	fahrenheit,
+};
+
+
+// Converts Kelvin temperatures, as the clients return them, to unit.
+// NAN slots stay NAN
+
+vector<double> convert_temperatures(const vector<double> &kelvin, temperature_unit unit) {
+	vector<double> ret;
+	ret.reserve(kelvin.size());
+	for (double k : kelvin) {
+		if (unit == temperature_unit::celsius)
+			ret.push_back(k - 273.15);
+		else if (unit == temperature_unit::fahrenheit)
+			ret.push_back((k - 273.15) * 9 / 5 + 32);
+		else
+			ret.push_back(k);
+	}
+	return ret;
+}
+
+
+// Lowest, highest and average temperature of a series, and how many
+// slots had a value
+
+struct forecast_summary {
+	double min;
+	double max;
+	double mean;
+	int count;
+};
+
+
+// The summary line of a chart, ignoring NAN slots. Empty when no
+// slot has a value
+
+optional<forecast_summary> summary(const vector<double> &temps) {
+	forecast_summary ret = {INFINITY, -INFINITY, 0, 0};
+	double sum = 0;
+	for (double t : temps) {
+		if (std::isnan(t))
+			continue;
+		ret.min = std::min(ret.min, t);
+		ret.max = std::max(ret.max, t);
+		sum += t;
+		ret.count++;
+	}
+	if (ret.count == 0) {
+		return nullopt;
+	}
+	ret.mean = sum / ret.count;
+	return ret;
+}
+
 
 
 class NonCachingClient {
@@ -56,53 +341,46 @@ class NonCachingClient {
 		<< lon;
 	    const string url = oss.str();
 	    RestClient::Response r = RestClient::get(url);
-	    auto parsed = json::parse(r.body);
-
-	    unsigned response_count = parsed["cnt"]; // number of responses
-	    vector<tuple<int, double>> data(response_count);
-	    for (auto &element : parsed["list"])
-	    {
-		data.push_back({element["dt"], element["main"]["temp"]});
-	    }
-	    return data;
+	    return parse_forecast(r.body);
 	}
 
 	public:
 
-	NonCachingClient(double lat, double lon) : lat(lat), lon(lon) {};
+	NonCachingClient(double lat, double lon) : lat(lat), lon(lon) {
+		check_coordinates(lat, lon);
+	};
 
-	vector<double> query(int start, int end) {
+	// one temperature per slot in [start, end), see sample_forecast.
+	// Pass sample_mode::linear for short minute resolution charts
+	vector<double> query(int start, int end, sample_mode mode = sample_mode::nearest,
+			     int granularity = 0, double percentile = 50) {
+		granularity = resolve_granularity(end - start, granularity);
+		check_alignment(start, end, granularity);
 		auto data = get_remote_data_five_day_forecast();
-		btree::map<int, double> data_map;
-		for (auto &tup : data) {
-			data_map.insert(tup);
-		}
-		auto granularity = ONE_HOUR;
-		auto requested_range = end - start;
-		if (requested_range < TWO_HOURS) {
-		    granularity = MINUTE;
-		} else if (requested_range < ONE_DAY) {
-		    granularity = FIVE_MINUTES;
-		};
-		vector<double> ret;
-		for (int i = start; i < end; i += granularity) {
-			auto low = data_map.lower_bound(i);
-			if (low == data_map.end()) {
-			} else if (low == data_map.begin()) {
-				ret.push_back(low->second);
-			} else {
-				auto prev = std::prev(low);
-				if ((i - prev->first) < (low->first - i))
-					ret.push_back(prev->second);
-				else
-					ret.push_back(low->second);
-			}
-		}
-		return ret;
+		return sample_forecast(data, start, end, mode, granularity, percentile);
 	}
 };
 
 
+// Snapshot of LFU_cache_client's counters, for a metrics endpoint
+
+struct cache_stats {
+	unsigned int remote_calls;	// fetches from the remote server
+	unsigned int hits;		// queries served from the cache
+	unsigned int misses;		// queries that needed a fetch
+	unsigned int evictions;		// entries dropped to make room
+};
+
+
+// A queried series as parallel columns, timestamps[k] being the
+// start of the slot temps[k] was sampled for
+
+struct forecast_columns {
+	vector<int> timestamps;
+	vector<double> temps;
+};
+
+
 /*   Client built with caches
  *
  *   In-memory design was used to build this caching system. The caching design implemented
@@ -133,30 +411,49 @@ class NonCachingClient {
 class LFU_cache_client {
 
 	unsigned int cache_size;  // cache size == len(hash map)
-	double client_lat, client_lon;
+	double client_lat = 0, client_lon = 0;
+	bool pair_set = false;	// whether set_pair was called, nothing can be fetched before
+	int precision = 4;	// decimals lat/lon are rounded to for the cache key
+	int min_granularity = 0;	// floor for automatically selected slots, 0 for none
 	std::map<key_pair, unsigned int> cache_frequency; // map for frequency
 	std::map<key_pair, std::vector<tuple<int, double>>> cache_data; // map for data
 	std::map<unsigned int, vector<key_pair>> freq_map;	// map containing frequencies and vector of keys for that frequency
+	unsigned int remote_calls = 0;	// number of times the remote server was actually queried
+	unsigned int hits = 0, misses = 0;	// lookups served from / missing in the cache
+	unsigned int evictions = 0;	// entries _delete dropped because the cache was full
+	int ttl;	// seconds a cached forecast stays fresh
+	std::function<time_t()> clock;	// source of the current time, injectable for tests
+	std::map<key_pair, time_t> cache_time; // map for time each pair was fetched
+
+        // fetches the rounded key's location, so every pair sharing a
+        // cache entry also shares the request that filled it
 
         std::vector<tuple<int, double>> get_remote_data_five_day_forecast(){
+            key_pair map_key_pair = _key();
             ostringstream oss; 
-            oss << "http://REDACTED"
-                << client_lat
+            oss << setprecision(15)
+                << "http://REDACTED"
+                << map_key_pair.first
                 << "&lon="
-                << client_lon;
+                << map_key_pair.second;
             const string url = oss.str();
+            remote_calls++;
             RestClient::Response r = RestClient::get(url);
-            auto parsed = json::parse(r.body);
-
-            unsigned response_count = parsed["cnt"]; // number of responses
-            vector<tuple<int, double>> data(response_count);
-            for (auto &element : parsed["list"])
-            {
-                data.push_back({element["dt"], element["main"]["temp"]});
-            }
-            return data;
+            return parse_forecast(r.body);
         }
 
+	// cache key for the current lat/lon, rounded to precision
+	// decimals so floating point noise doesn't cause a cache miss
+
+	key_pair _key(){
+		if (!pair_set) {
+			throw invalid_argument("set_pair must be called before querying");
+		}
+		double scale = std::pow(10.0, precision);
+		return std::make_pair(std::round(client_lat * scale) / scale,
+				      std::round(client_lon * scale) / scale);
+	}
+
 	// Deletes a specific key from vector within freq_map
 	// done when frequency changes for specific key
         
@@ -186,6 +483,7 @@ class LFU_cache_client {
                 if(freq_map.empty()){
                         return;
                 }
+                evictions++;
                 key_pair temp_key = freq_map.begin()->second.front();
                 _erase(freq_map.begin()->first, temp_key);
                 if(freq_map.begin()->second.empty()){
@@ -193,84 +491,231 @@ class LFU_cache_client {
                 }
                 cache_frequency.erase(temp_key);
                 cache_data.erase(temp_key);
+                cache_time.erase(temp_key);
+        }
+
+        // removes a specific key from all maps (ttl expiry or
+        // explicit invalidate)
+
+        void _remove(key_pair map_key_pair){
+                unsigned int count = cache_frequency[map_key_pair];
+                _erase(count, map_key_pair);
+                if(freq_map[count].empty()){
+                        freq_map.erase(count);
+                }
+                cache_frequency.erase(map_key_pair);
+                cache_data.erase(map_key_pair);
+                cache_time.erase(map_key_pair);
         }
 
+	// true if the pair has cached data that is still within the ttl,
+	// without counting it as a hit or touching its frequency
+
+	bool _fresh(key_pair map_key_pair){
+		return cache_data.find(map_key_pair)!=cache_data.end()
+		       && clock() - cache_time[map_key_pair] <= ttl;
+	}
+
         // pulls data, checks to see if map is full. If it is,
         // call _delete to remove LFU, if not simply insert into maps(cache)
 
         vector<tuple<int, double>> _put(){
-                key_pair map_key_pair = std::make_pair(client_lat, client_lon);
+                key_pair map_key_pair = _key();
                 auto result = get_remote_data_five_day_forecast();
                 if(cache_data.size() >= cache_size){
                         _delete();
                 }
                 cache_data.insert({map_key_pair, result});
                 cache_frequency.insert({map_key_pair, 1});
+                cache_time.insert({map_key_pair, clock()});
                 _add(cache_frequency[map_key_pair], map_key_pair);
                 return result;
         }
 
 	public:
 
-	// Set cache size
-	LFU_cache_client(unsigned int cache_size) : cache_size(cache_size) {};
+	// Set cache size, and optionally how long cached data stays
+	// fresh and where the current time comes from
+	LFU_cache_client(unsigned int cache_size, int ttl = TEN_MINUTES,
+			 std::function<time_t()> clock = []() { return time(nullptr); })
+		: cache_size(cache_size), ttl(ttl), clock(clock) {};
 	
 	// define lat/lon
 	void set_pair(double lat, double lon){
+		check_coordinates(lat, lon);
 		client_lat = lat; 
 		client_lon = lon;
+		pair_set = true;
 	} 
+
+	// define how many decimals of lat/lon tell locations apart,
+	// fewer decimals means more queries share a cached entry. Up to
+	// 12, as the request prints 15 significant digits and longitude
+	// can have 3 before the point. Drops whatever is cached, since
+	// entries keyed at the old precision can't be looked up anymore
+	void set_precision(int decimals){
+		if (decimals < 0 || decimals > 12) {
+			throw invalid_argument("precision must be within [0, 12]");
+		}
+		precision = decimals;
+		_clear();
+	}
+
+	// define the coarsest slot size automatic selection may go below,
+	// e.g. FIVE_MINUTES keeps short ranges from getting minute slots.
+	// 0 removes the floor, a forced granularity in query ignores it
+	void set_min_granularity(int granularity){
+		if (granularity != 0) {
+			check_granularity(granularity);
+		}
+		min_granularity = granularity;
+	}
 	
 	// looks for pair(lat/lon) in map, if found(hit)
 	// add corresponding frequencies to map and return results
-	// if not found(cache miss) call _put()
+	// if not found(cache miss) or older than the ttl call _put()
 
 	vector<tuple<int, double>> _get(){
-		auto map_key_pair = std::make_pair(client_lat,client_lon);
+		auto map_key_pair = _key();
+		if(cache_data.find(map_key_pair)!=cache_data.end()
+		   && clock() - cache_time[map_key_pair] > ttl){
+			_remove(map_key_pair);
+		}
 		if(cache_data.find(map_key_pair)!=cache_data.end()){
+			hits++;
 			auto result = cache_data[map_key_pair];
 			_erase(cache_frequency[map_key_pair], map_key_pair);
 			cache_frequency[map_key_pair]++;
 			_add(cache_frequency[map_key_pair], map_key_pair);
 			return result;
 		}
+		misses++;
 		return _put();
 	}
+
+	// loads the current pair into the cache ahead of time so
+	// later queries are served without a remote call. Doesn't count
+	// as a query, so hit/miss counts and LFU frequency are untouched
+
+	void prefetch(){
+		auto map_key_pair = _key();
+		if(_fresh(map_key_pair)){
+			return;
+		}
+		if(cache_data.find(map_key_pair)!=cache_data.end()){
+			_remove(map_key_pair);
+		}
+		_put();
+	}
+
+	// number of remote fetches made so far, lets tests verify
+	// that repeated queries were served from the cache
+
+	unsigned int remote_call_count(){
+		return remote_calls;
+	}
+
+	// number of queries served from the cache / needing a fetch
+
+	unsigned int hit_count(){
+		return hits;
+	}
+
+	unsigned int miss_count(){
+		return misses;
+	}
+
+	// all counters at once. The request's stale_serves and
+	// bytes_cached are left out: an expired entry is never served,
+	// and payload sizes aren't tracked
+
+	cache_stats stats(){
+		return {remote_calls, hits, misses, evictions};
+	}
+
+	// drops the current pair's cached data so the next
+	// query for it is fetched again, regardless of ttl
+
+	void invalidate(){
+		auto map_key_pair = _key();
+		if(cache_data.find(map_key_pair)!=cache_data.end()){
+			_remove(map_key_pair);
+		}
+	}
+
 	void _clear(){
 		cache_frequency.clear();
 		cache_data.clear();
 		freq_map.clear();
+		cache_time.clear();
 	}
 
-        vector<double> query(int start, int end) {
+        // one temperature per slot in [start, end), served from the cache,
+        // see sample_forecast. Pass sample_mode::linear for short minute
+        // resolution charts
+        vector<double> query(int start, int end, sample_mode mode = sample_mode::nearest,
+                             int granularity = 0, double percentile = 50) {
+                granularity = resolve_granularity(end - start, granularity, min_granularity);
+                check_alignment(start, end, granularity);
+                // OpenWeather forecasts five days ahead, nothing later can be served
+                if (end > clock() + FIVE_DAYS) {
+                        throw out_of_range("end is more than five days past now");
+                }
                 auto data = _get(); // calls _get() instead of restapi method, this allows to check if data is in cache.
-                btree::map<int, double> data_map;
-                for (auto &tup : data) {
-                        data_map.insert(tup);
+                return sample_forecast(data, start, end, mode, granularity, percentile);
+        }
+
+        // exactly count slots of granularity seconds from start, for a
+        // chart scrolling forward a window at a time. Slots past the
+        // last sample are NAN, as with query
+
+        vector<double> query_window(int start, int count, int granularity,
+                                    sample_mode mode = sample_mode::nearest, double percentile = 50) {
+                check_granularity(granularity);
+                if (count < 0) {
+                        throw invalid_argument("count must not be negative");
+                }
+                long long end = (long long)start + (long long)count * granularity;
+                if (end > numeric_limits<int>::max()) {
+                        throw invalid_argument("window ends past the latest representable time");
                 }
-                auto granularity = ONE_HOUR;
-                auto requested_range = end - start;
-                if (requested_range < TWO_HOURS) {
-                    granularity = MINUTE;
-                } else if (requested_range < ONE_DAY) {
-                    granularity = FIVE_MINUTES;
-                };
-                vector<double> ret;
-                for (int i = start; i < end; i += granularity) {
-                        auto low = data_map.lower_bound(i);
-                        if (low == data_map.end()) {
-                        } else if (low == data_map.begin()) {
-                                ret.push_back(low->second);
-                        } else {
-                                auto prev = std::prev(low);
-                                if ((i - prev->first) < (low->first - i))
-                                        ret.push_back(prev->second);
-                                else
-                                        ret.push_back(low->second);
+                return query(start, (int)end, mode, granularity, percentile);
+        }
+
+        // the fetched (dt, temp) samples with dt in [start, end), at the
+        // server's own 3 hour spacing with no slots or interpolation.
+        // Served from the cache like query, and counted the same way
+
+        vector<tuple<int, double>> query_raw(int start, int end) {
+                vector<tuple<int, double>> ret;
+                for (auto &tup : _get()) {
+                        if (get<0>(tup) >= start && get<0>(tup) < end) {
+                                ret.push_back(tup);
                         }
                 }
                 return ret;
         }
+
+        // every fetched sample, first to last, for an overview chart
+
+        vector<tuple<int, double>> query_all() {
+                return query_raw(numeric_limits<int>::min(), numeric_limits<int>::max());
+        }
+
+        // query's series alongside the start time of each slot, ready
+        // to hand to a columnar (Arrow style) consumer
+
+        forecast_columns query_columns(int start, int end, sample_mode mode = sample_mode::nearest,
+                                       int granularity = 0, double percentile = 50) {
+                forecast_columns columns;
+                columns.temps = query(start, end, mode, granularity, percentile);
+                granularity = resolve_granularity(end - start, granularity, min_granularity);
+                columns.timestamps.reserve(columns.temps.size());
+                for (size_t k = 0; k < columns.temps.size(); k++) {
+                        columns.timestamps.push_back(start + k * granularity);
+                }
+                return columns;
+        }
 	
 };
 
@@ -279,7 +724,269 @@ class LFU_cache_client {
 
 go_bandit([]() {
 	const int SAMPLE_DATA_START = 1659722400;
+	describe("parse_forecast", []() {
+		it("reads dt and temp from each element", [&]() {
+			auto data = parse_forecast(R"({"cod":"200","message":0,"cnt":2,"list":[)"
+						   R"({"dt":0,"main":{"temp":280.5}},)"
+						   R"({"dt":10800,"main":{"temp":281.5}}]})");
+			AssertThat(data.size(), Equals(2));
+			AssertThat(get<0>(data[1]), Equals(10800));
+			AssertThat(get<1>(data[1]), Equals(281.5));
+		});
+		it("rejects a string error cod", [&]() {
+			AssertThrows(runtime_error, parse_forecast(R"({"cod":"404","message":"city not found"})"));
+			AssertThat(string(LastException<runtime_error>().what()), Contains("city not found"));
+		});
+		it("rejects a numeric error cod", [&]() {
+			AssertThrows(runtime_error, parse_forecast(R"({"cod":401,"message":"Invalid API key"})"));
+			AssertThat(string(LastException<runtime_error>().what()), Contains("401"));
+			AssertThat(string(LastException<runtime_error>().what()), Contains("Invalid API key"));
+		});
+		it("rejects a dt_txt that doesn't match dt", [&]() {
+			AssertThrows(runtime_error, parse_forecast(R"({"cod":"200","cnt":1,"list":[)"
+								   R"({"dt":0,"dt_txt":"1970-01-01 03:00:00","main":{"temp":280.5}}]})"));
+		});
+	});
+	describe("format_dt_txt", []() {
+		it("formats dt as a UTC label", [&]() {
+			AssertThat(format_dt_txt(0), Equals("1970-01-01 00:00:00"));
+		});
+	});
+	describe("select_granularity", []() {
+		it("uses minutes just under two hours", [&]() {
+			AssertThat(select_granularity(TWO_HOURS - 1), Equals(MINUTE));
+		});
+		it("uses five minutes at exactly two hours", [&]() {
+			AssertThat(select_granularity(TWO_HOURS), Equals(FIVE_MINUTES));
+		});
+		it("uses five minutes just under one day", [&]() {
+			AssertThat(select_granularity(ONE_DAY - 1), Equals(FIVE_MINUTES));
+		});
+		it("uses an hour at exactly one day", [&]() {
+			AssertThat(select_granularity(ONE_DAY), Equals(ONE_HOUR));
+		});
+	});
+	describe("resolve_granularity", []() {
+		it("selects automatically when not forced", [&]() {
+			AssertThat(resolve_granularity(30 * MINUTE, 0), Equals(MINUTE));
+		});
+		it("keeps a forced granularity", [&]() {
+			AssertThat(resolve_granularity(30 * MINUTE, ONE_HOUR), Equals(ONE_HOUR));
+		});
+		it("rejects unsupported granularities", [&]() {
+			AssertThrows(invalid_argument, resolve_granularity(ONE_HOUR, 120));
+			AssertThrows(invalid_argument, resolve_granularity(ONE_HOUR, -60));
+		});
+		it("raises an automatic choice to the floor", [&]() {
+			AssertThat(resolve_granularity(30 * MINUTE, 0, FIVE_MINUTES), Equals(FIVE_MINUTES));
+			AssertThat(resolve_granularity(ONE_DAY, 0, FIVE_MINUTES), Equals(ONE_HOUR));
+		});
+	});
+	describe("check_granularity", []() {
+		it("accepts the selectable slot sizes", [&]() {
+			check_granularity(MINUTE);
+			check_granularity(FIVE_MINUTES);
+			check_granularity(ONE_HOUR);
+		});
+		it("rejects anything else", [&]() {
+			AssertThrows(invalid_argument, check_granularity(0));
+			AssertThrows(invalid_argument, check_granularity(120));
+		});
+	});
+	describe("check_alignment", []() {
+		it("accepts an aligned range", [&]() {
+			check_alignment(ONE_DAY, 2 * ONE_DAY, ONE_HOUR);
+			check_alignment(0, 90 * MINUTE, MINUTE);
+		});
+		it("rejects a misaligned start", [&]() {
+			AssertThrows(invalid_argument, check_alignment(30, 30 + ONE_HOUR, MINUTE));
+		});
+		it("rejects a length that isn't a whole number of slots", [&]() {
+			AssertThrows(invalid_argument, check_alignment(0, 90, MINUTE));
+		});
+	});
+	describe("sample_forecast", []() {
+		// samples every 3 hours from 0 to 27 hours, 280.0, 281.0, ...
+		vector<tuple<int, double>> samples;
+		for (int i = 0; i < 10; i++) {
+			samples.push_back({i * 3 * ONE_HOUR, 280.0 + i});
+		}
+		it("uses minute slots under two hours", [&]() {
+			auto data = sample_forecast(samples, 0, ONE_HOUR);
+			AssertThat(data.size(), Equals(60));
+			AssertThat(data[0], Equals(280.0));
+			AssertThat(data[59], Equals(280.0));
+		});
+		it("uses five minute slots under a day", [&]() {
+			auto data = sample_forecast(samples, 0, 6 * ONE_HOUR);
+			AssertThat(data.size(), Equals(72));
+			AssertThat(data[17], Equals(280.0));
+			AssertThat(data[20], Equals(281.0));
+			AssertThat(data[36], Equals(281.0));
+		});
+		it("uses hour slots from a day up", [&]() {
+			auto data = sample_forecast(samples, 0, ONE_DAY);
+			AssertThat(data.size(), Equals(24));
+			AssertThat(data[3], Equals(281.0));
+			AssertThat(data[23], Equals(288.0));
+		});
+		it("rises smoothly between samples at minute resolution in linear mode", [&]() {
+			auto data = sample_forecast(samples, 0, ONE_HOUR, sample_mode::linear);
+			AssertThat(data.size(), Equals(60));
+			for (unsigned i = 1; i < data.size(); i++) {
+				AssertThat(data[i], IsGreaterThan(data[i - 1]));
+			}
+			AssertThat(data[59], EqualsWithDelta(280.0 + 59.0 / 180.0, 1e-9));
+		});
+		it("averages the samples at the midpoint in linear mode", [&]() {
+			auto data = sample_forecast(samples, 0, 6 * ONE_HOUR, sample_mode::linear);
+			AssertThat(data[18], Equals((280.0 + 281.0) / 2));
+		});
+		it("keeps exact samples unchanged in linear mode", [&]() {
+			auto data = sample_forecast(samples, 0, 6 * ONE_HOUR, sample_mode::linear);
+			AssertThat(data[0], Equals(280.0));
+			AssertThat(data[36], Equals(281.0));
+		});
+		it("gives NAN outside the samples in linear mode", [&]() {
+			vector<tuple<int, double>> single = {{30 * MINUTE, 280.0}};
+			auto data = sample_forecast(single, 0, ONE_HOUR, sample_mode::linear);
+			AssertThat(data.size(), Equals(60));
+			AssertThat(std::isnan(data[0]), IsTrue());
+			AssertThat(std::isnan(data[29]), IsTrue());
+			AssertThat(data[30], Equals(280.0));
+			AssertThat(std::isnan(data[31]), IsTrue());
+		});
+		it("gives NAN after the last sample", [&]() {
+			auto data = sample_forecast(samples, 0, 2 * ONE_DAY);
+			AssertThat(data.size(), Equals(48));
+			AssertThat(data[27], Equals(289.0));
+			AssertThat(std::isnan(data[28]), IsTrue());
+			AssertThat(std::isnan(data[47]), IsTrue());
+		});
+		it("gives no slots for an empty range", [&]() {
+			AssertThat(sample_forecast(samples, 0, 0).size(), Equals(0));
+		});
+		it("gives one slot for a range shorter than a step", [&]() {
+			AssertThat(sample_forecast(samples, 0, 30).size(), Equals(1));
+		});
+		it("gives one slot for a range of exactly one step", [&]() {
+			AssertThat(sample_forecast(samples, 0, MINUTE).size(), Equals(1));
+		});
+		it("starts the first slot at start", [&]() {
+			vector<tuple<int, double>> per_minute = {{0, 280.0}, {MINUTE, 290.0}, {ONE_HOUR, 300.0}};
+			auto data = sample_forecast(per_minute, 0, ONE_HOUR);
+			AssertThat(data.size(), Equals(60));
+			AssertThat(data[0], Equals(280.0));
+			AssertThat(data[1], Equals(290.0));
+		});
+		it("uses a forced granularity", [&]() {
+			auto data = sample_forecast(samples, 0, 30 * MINUTE, sample_mode::nearest, ONE_HOUR);
+			AssertThat(data.size(), Equals(1));
+			AssertThat(data[0], Equals(280.0));
+		});
+		it("averages samples that share a dt", [&]() {
+			vector<tuple<int, double>> repeated = {{0, 280.0}, {0, 282.0}, {3 * ONE_HOUR, 290.0}};
+			auto data = sample_forecast(repeated, 0, ONE_HOUR);
+			AssertThat(data.size(), Equals(60));
+			AssertThat(data[0], Equals(281.0));
+		});
+		it("handles a single sample", [&]() {
+			vector<tuple<int, double>> single = {{30 * MINUTE, 280.0}};
+			auto data = sample_forecast(single, 0, ONE_HOUR);
+			AssertThat(data.size(), Equals(60));
+			AssertThat(data[0], Equals(280.0));
+			AssertThat(data[30], Equals(280.0));
+			AssertThat(std::isnan(data[31]), IsTrue());
+		});
+		it("bridges a gap in the samples with the nearest side", [&]() {
+			vector<tuple<int, double>> gapped = {{0, 280.0}, {3 * ONE_HOUR, 281.0},
+							      {12 * ONE_HOUR, 284.0}, {15 * ONE_HOUR, 285.0}};
+			auto data = sample_forecast(gapped, 0, ONE_DAY);
+			AssertThat(data.size(), Equals(24));
+			AssertThat(data[7], Equals(281.0));
+			AssertThat(data[8], Equals(284.0));
+			AssertThat(data[15], Equals(285.0));
+			AssertThat(std::isnan(data[16]), IsTrue());
+		});
+		it("gives empty slots NAN when aggregating", [&]() {
+			auto data = sample_forecast(samples, 0, 2 * ONE_DAY, sample_mode::mean);
+			AssertThat(data.size(), Equals(48));
+			AssertThat(data[0], Equals(280.0));
+			AssertThat(std::isnan(data[1]), IsTrue());
+			AssertThat(std::isnan(data[47]), IsTrue());
+		});
+	});
+	describe("sample_forecast aggregation", []() {
+		// samples every 20 minutes cycling 280.0, 281.0, 282.0
+		vector<tuple<int, double>> samples;
+		for (int i = 0; i < 72; i++) {
+			samples.push_back({i * 20 * MINUTE, 280.0 + i % 3});
+		}
+		it("averages the samples in a slot in mean mode", [&]() {
+			auto nearest = sample_forecast(samples, 0, ONE_DAY);
+			auto mean = sample_forecast(samples, 0, ONE_DAY, sample_mode::mean);
+			AssertThat(nearest.size(), Equals(mean.size()));
+			AssertThat(nearest[0], Equals(280.0));
+			AssertThat(mean[0], Equals(281.0));
+		});
+		it("takes the extremes in a slot in max and min mode", [&]() {
+			auto max = sample_forecast(samples, 0, ONE_DAY, sample_mode::max);
+			auto min = sample_forecast(samples, 0, ONE_DAY, sample_mode::min);
+			AssertThat(max[0], Equals(282.0));
+			AssertThat(min[0], Equals(280.0));
+		});
+		it("matches mean with the 50th percentile on evenly spread samples", [&]() {
+			auto mean = sample_forecast(samples, 0, ONE_DAY, sample_mode::mean);
+			auto median = sample_forecast(samples, 0, ONE_DAY, sample_mode::percentile, 0, 50);
+			AssertThat(median.size(), Equals(mean.size()));
+			AssertThat(median[0], Equals(mean[0]));
+			AssertThat(median[23], Equals(mean[23]));
+		});
+		it("blends the nearest ranks for other percentiles", [&]() {
+			auto data = sample_forecast(samples, 0, ONE_DAY, sample_mode::percentile, 0, 90);
+			AssertThat(data[0], EqualsWithDelta(281.8, 1e-9));
+			data = sample_forecast(samples, 0, ONE_DAY, sample_mode::percentile, 0, 100);
+			AssertThat(data[0], Equals(282.0));
+		});
+		it("rejects a percentile outside [0, 100]", [&]() {
+			AssertThrows(invalid_argument, sample_forecast(samples, 0, ONE_DAY, sample_mode::percentile, 0, -1));
+			AssertThrows(invalid_argument, sample_forecast(samples, 0, ONE_DAY, sample_mode::percentile, 0, 101));
+		});
+	});
+	describe("convert_temperatures", []() {
+		it("converts freezing point to Celsius and Fahrenheit", [&]() {
+			AssertThat(convert_temperatures({273.15}, temperature_unit::celsius)[0], Equals(0.0));
+			AssertThat(convert_temperatures({273.15}, temperature_unit::fahrenheit)[0], Equals(32.0));
+			AssertThat(convert_temperatures({273.15}, temperature_unit::kelvin)[0], Equals(273.15));
+		});
+		it("keeps NAN slots", [&]() {
+			auto data = convert_temperatures({NAN, 283.15}, temperature_unit::celsius);
+			AssertThat(data.size(), Equals(2));
+			AssertThat(std::isnan(data[0]), IsTrue());
+			AssertThat(data[1], EqualsWithDelta(10.0, 1e-9));
+		});
+	});
+	describe("summary", []() {
+		it("is empty when every slot is missing", [&]() {
+			AssertThat(summary({NAN, NAN}).has_value(), IsFalse());
+			AssertThat(summary({}).has_value(), IsFalse());
+		});
+		it("skips missing slots", [&]() {
+			auto s = summary({NAN, 280.0, 284.0, NAN, 282.0});
+			AssertThat(s.has_value(), IsTrue());
+			AssertThat(s->min, Equals(280.0));
+			AssertThat(s->max, Equals(284.0));
+			AssertThat(s->mean, Equals(282.0));
+			AssertThat(s->count, Equals(3));
+		});
+	});
 	describe("remote_data", []() {
+		it("rejects a NaN latitude", [&]() {
+			AssertThrows(invalid_argument, NonCachingClient(NAN, -122.19));
+		});
+		it("rejects an out of range longitude", [&]() {
+			AssertThrows(invalid_argument, NonCachingClient(47.36, 200.0));
+		});
 		it("demonstrates interpolation", [&]() {
 			auto start = SAMPLE_DATA_START;
 			auto end = start + 25 * ONE_HOUR;
@@ -289,6 +996,30 @@ go_bandit([]() {
 			AssertThat(data[0], Equals(290.18));
 			AssertThat(data[1], Equals(290.18));
 		});
+		it("averages only the hours holding a 3 hour sample", [&]() {
+			auto start = SAMPLE_DATA_START;
+			auto end = start + 25 * ONE_HOUR;
+			auto client = NonCachingClient(47.36, -122.19);
+			auto nearest = client.query(start, end);
+			auto mean = client.query(start, end, sample_mode::mean);
+			AssertThat(mean.size(), Equals(nearest.size()));
+			AssertThat(mean[0], Equals(nearest[0]));
+			AssertThat(mean[3], Equals(294.06));
+			AssertThat(mean[3], Equals(nearest[3]));
+			AssertThat(std::isnan(mean[1]), IsTrue());
+			AssertThat(std::isnan(mean[2]), IsTrue());
+		});
+		it("rejects a misaligned range before fetching", [&]() {
+			auto client = NonCachingClient(47.36, -122.19);
+			AssertThrows(invalid_argument, client.query(SAMPLE_DATA_START + 30,
+								    SAMPLE_DATA_START + 25 * ONE_HOUR));
+		});
+		it("only uses samples returned by the server", [&]() {
+			auto client = NonCachingClient(47.36, -122.19);
+			auto data = client.query(0, ONE_HOUR);
+			AssertThat(data.size(), Equals(60));
+			AssertThat(data[0], Equals(290.18));
+		});
 
 	});
 	describe("cache_data", []() {
@@ -302,6 +1033,318 @@ go_bandit([]() {
                         AssertThat(data_cache[0], Equals(290.18));
                         AssertThat(data_cache[1], Equals(290.18));
                 });
+		it("expects single remote call for overlapping ranges", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto minutes = cache.query(start_cache, start_cache + ONE_HOUR);
+			auto five_minutes = cache.query(start_cache, start_cache + 12 * ONE_HOUR);
+			auto hours = cache.query(start_cache, start_cache + 3 * ONE_DAY);
+			AssertThat(minutes.size(), Equals(60));
+			AssertThat(five_minutes.size(), Equals(144));
+			AssertThat(hours.size(), Equals(72));
+			AssertThat(cache.remote_call_count(), Equals(1));
+		});
+		it("tracks cache hits and misses", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			for (int i = 0; i < 3; i++) {
+				cache.query(start_cache, end_cache);
+			}
+			cache.set_pair(45.62, -122.67);
+			for (int i = 0; i < 2; i++) {
+				cache.query(start_cache, end_cache);
+			}
+			AssertThat(cache.miss_count(), Equals(2));
+			AssertThat(cache.hit_count(), Equals(3));
+			AssertThat(cache.remote_call_count(), Equals(2));
+		});
+		it("fetches each location once", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			cache.query(start_cache, end_cache);
+			cache.set_pair(45.62, -122.67);
+			cache.query(start_cache, end_cache);
+			AssertThat(cache.remote_call_count(), Equals(2));
+			cache.set_pair(47.36 + 1e-9, -122.19);
+			cache.query(start_cache, end_cache);
+			cache.set_pair(45.62, -122.67);
+			cache.query(start_cache, end_cache);
+			AssertThat(cache.remote_call_count(), Equals(2));
+		});
+		it("refetches once the ttl has passed", [&]() {
+			time_t now = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10, TEN_MINUTES, [&now]() { return now; });
+			cache.set_pair(47.36, -122.19);
+			cache.query(SAMPLE_DATA_START, SAMPLE_DATA_START + ONE_HOUR);
+			now += TEN_MINUTES + 1;
+			cache.query(SAMPLE_DATA_START, SAMPLE_DATA_START + ONE_HOUR);
+			AssertThat(cache.remote_call_count(), Equals(2));
+		});
+		it("serves from the cache within the ttl", [&]() {
+			time_t now = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10, TEN_MINUTES, [&now]() { return now; });
+			cache.set_pair(47.36, -122.19);
+			cache.query(SAMPLE_DATA_START, SAMPLE_DATA_START + ONE_HOUR);
+			now += TEN_MINUTES;
+			cache.query(SAMPLE_DATA_START, SAMPLE_DATA_START + ONE_HOUR);
+			AssertThat(cache.remote_call_count(), Equals(1));
+		});
+		it("rejects a misaligned range without a remote call", [&]() {
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			AssertThrows(invalid_argument, cache.query(SAMPLE_DATA_START, SAMPLE_DATA_START + 90));
+			AssertThat(cache.remote_call_count(), Equals(0));
+		});
+		it("rejects an end more than five days out", [&]() {
+			time_t now = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10, TEN_MINUTES, [&now]() { return now; });
+			cache.set_pair(47.36, -122.19);
+			AssertThrows(out_of_range, cache.query(SAMPLE_DATA_START, SAMPLE_DATA_START + 6 * ONE_DAY));
+			AssertThat(cache.remote_call_count(), Equals(0));
+			AssertThat(cache.query(SAMPLE_DATA_START, SAMPLE_DATA_START + 5 * ONE_DAY).size(), Equals(120));
+		});
+		it("serves queries from a prefetched forecast", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			cache.prefetch();
+			cache.prefetch();
+			AssertThat(cache.remote_call_count(), Equals(1));
+			AssertThat(cache.hit_count(), Equals(0));
+			AssertThat(cache.miss_count(), Equals(0));
+			cache.query(start_cache, start_cache + ONE_HOUR);
+			cache.query(start_cache, start_cache + 12 * ONE_HOUR);
+			cache.query(start_cache, start_cache + 3 * ONE_DAY);
+			AssertThat(cache.remote_call_count(), Equals(1));
+			AssertThat(cache.hit_count(), Equals(3));
+			AssertThat(cache.miss_count(), Equals(0));
+		});
+		it("prefetches again once the ttl has passed", [&]() {
+			time_t now = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10, TEN_MINUTES, [&now]() { return now; });
+			cache.set_pair(47.36, -122.19);
+			cache.prefetch();
+			now += TEN_MINUTES + 1;
+			cache.prefetch();
+			AssertThat(cache.remote_call_count(), Equals(2));
+		});
+		it("queries at a forced granularity", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto hours = cache.query(start_cache, start_cache + 2 * ONE_HOUR,
+						 sample_mode::nearest, ONE_HOUR);
+			AssertThat(hours.size(), Equals(2));
+			AssertThrows(invalid_argument, cache.query(start_cache, start_cache + 2 * ONE_HOUR,
+								   sample_mode::nearest, 120));
+		});
+		it("returns the raw samples in a range", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto raw = cache.query_raw(start_cache, start_cache + 12 * ONE_HOUR);
+			AssertThat(raw.size(), Equals(4));
+			for (unsigned i = 0; i < raw.size(); i++) {
+				AssertThat(get<0>(raw[i]), Equals(start_cache + int(i) * 3 * ONE_HOUR));
+			}
+			AssertThat(get<1>(raw[0]), Equals(290.18));
+			AssertThat(get<1>(raw[1]), Equals(294.06));
+			AssertThat(get<1>(raw[2]), Equals(297.57));
+			AssertThat(get<1>(raw[3]), Equals(292.31));
+			cache.query_raw(start_cache, start_cache + ONE_DAY);
+			AssertThat(cache.remote_call_count(), Equals(1));
+		});
+		it("reports every counter in stats", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(1);
+			cache.set_pair(47.36, -122.19);
+			cache.query(start_cache, end_cache);
+			cache.query(start_cache, end_cache);
+			cache.set_pair(45.62, -122.67);
+			cache.query(start_cache, end_cache);
+			cache.set_pair(47.36, -122.19);
+			cache.query(start_cache, end_cache);
+			auto stats = cache.stats();
+			AssertThat(stats.remote_calls, Equals(3));
+			AssertThat(stats.hits, Equals(1));
+			AssertThat(stats.misses, Equals(3));
+			AssertThat(stats.evictions, Equals(2));
+		});
+		it("shares entries for nearby locations at low precision", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_precision(2);
+			cache.set_pair(47.36, -122.19);
+			cache.query(start_cache, end_cache);
+			cache.set_pair(47.3649, -122.19);
+			cache.query(start_cache, end_cache);
+			AssertThat(cache.remote_call_count(), Equals(1));
+		});
+		it("separates nearby locations at high precision", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_precision(4);
+			cache.set_pair(47.36, -122.19);
+			cache.query(start_cache, end_cache);
+			cache.set_pair(47.3649, -122.19);
+			cache.query(start_cache, end_cache);
+			AssertThat(cache.remote_call_count(), Equals(2));
+		});
+		it("fetches the rounded location", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_precision(2);
+			cache.set_pair(47.3649, -122.1851);
+			auto data = cache.query(start_cache, start_cache + 25 * ONE_HOUR);
+			AssertThat(data[0], Equals(290.18));
+		});
+		it("drops cached entries when the precision changes", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			cache.query(start_cache, end_cache);
+			cache.set_precision(2);
+			cache.query(start_cache, end_cache);
+			AssertThat(cache.remote_call_count(), Equals(2));
+		});
+		it("rejects an unusable precision", [&]() {
+			auto cache = LFU_cache_client(10);
+			AssertThrows(invalid_argument, cache.set_precision(-1));
+			AssertThrows(invalid_argument, cache.set_precision(13));
+		});
+		it("rejects a repeated range past the horizon without fetching", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 6 * ONE_DAY;
+			auto cache = LFU_cache_client(10, TEN_MINUTES, [&]() { return start_cache; });
+			cache.set_pair(47.36, -122.19);
+			AssertThrows(out_of_range, cache.query(start_cache, end_cache));
+			AssertThrows(out_of_range, cache.query(start_cache, end_cache));
+			AssertThat(cache.remote_call_count(), Equals(0));
+		});
+		it("applies a minimum granularity", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			cache.set_min_granularity(FIVE_MINUTES);
+			auto data = cache.query(start_cache, start_cache + ONE_HOUR);
+			AssertThat(data.size(), Equals(12));
+			AssertThrows(invalid_argument, cache.set_min_granularity(120));
+		});
+		it("refetches after the cache is cleared", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			cache.query(start_cache, end_cache);
+			cache._clear();
+			cache.query(start_cache, end_cache);
+			AssertThat(cache.remote_call_count(), Equals(2));
+		});
+		it("refetches only the invalidated location", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(45.62, -122.67);
+			cache.query(start_cache, end_cache);
+			cache.set_pair(47.36, -122.19);
+			cache.query(start_cache, end_cache);
+			cache.invalidate();
+			cache.query(start_cache, end_cache);
+			AssertThat(cache.remote_call_count(), Equals(3));
+			cache.set_pair(45.62, -122.67);
+			cache.query(start_cache, end_cache);
+			AssertThat(cache.remote_call_count(), Equals(3));
+		});
+		it("returns aligned timestamp and temperature columns", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 25 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto columns = cache.query_columns(start_cache, end_cache);
+			auto data = cache.query(start_cache, end_cache);
+			AssertThat(columns.timestamps.size(), Equals(columns.temps.size()));
+			AssertThat(columns.timestamps[0], Equals(start_cache));
+			AssertThat(columns.timestamps[3], Equals(start_cache + 3 * ONE_HOUR));
+			AssertThat(columns.temps[3], Equals(data[3]));
+		});
+		it("queries a window of slots", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto data = cache.query_window(start_cache, 30, FIVE_MINUTES);
+			AssertThat(data.size(), Equals(30));
+			// the 3 hour samples meet halfway, at the 18th five minute slot
+			AssertThat(data[17], Equals(290.18));
+			AssertThat(data[18], Equals(294.06));
+			AssertThrows(invalid_argument, cache.query_window(start_cache, 30, 0));
+			AssertThrows(invalid_argument, cache.query_window(start_cache, 30, 120));
+		});
+		it("pads a window past the last sample to count slots", [&]() {
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto data = cache.query_window(1660143600, 12, ONE_HOUR);
+			AssertThat(data.size(), Equals(12));
+			AssertThat(std::isnan(data[0]), IsFalse());
+			AssertThat(std::isnan(data[1]), IsTrue());
+		});
+		it("rejects an unusable window", [&]() {
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			AssertThrows(invalid_argument, cache.query_window(SAMPLE_DATA_START, -1, ONE_HOUR));
+			AssertThrows(invalid_argument, cache.query_window(numeric_limits<int>::max() - ONE_HOUR, 2, ONE_HOUR));
+			AssertThat(cache.remote_call_count(), Equals(0));
+		});
+		it("labels raw samples like the server", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto raw = cache.query_raw(start_cache, start_cache + 12 * ONE_HOUR);
+			AssertThat(format_dt_txt(get<0>(raw[0])), Equals("2022-08-05 18:00:00"));
+		});
+		it("returns every sample of the forecast", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto all = cache.query_all();
+			AssertThat(all.size(), Equals(40));
+			AssertThat(get<0>(all.front()), Equals(start_cache));
+			AssertThat(get<0>(all.back()), Equals(1660143600));
+			cache.query_all();
+			AssertThat(cache.remote_call_count(), Equals(1));
+		});
+		it("serves one range at every granularity from one fetch", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto end_cache = start_cache + 6 * ONE_HOUR;
+			auto cache = LFU_cache_client(10);
+			cache.set_pair(47.36, -122.19);
+			auto minutes = cache.query(start_cache, end_cache, sample_mode::nearest, MINUTE);
+			auto five_minutes = cache.query(start_cache, end_cache, sample_mode::nearest, FIVE_MINUTES);
+			auto hours = cache.query(start_cache, end_cache, sample_mode::nearest, ONE_HOUR);
+			AssertThat(minutes.size(), Equals(360));
+			AssertThat(five_minutes.size(), Equals(72));
+			AssertThat(hours.size(), Equals(6));
+			AssertThat(cache.remote_call_count(), Equals(1));
+		});
+		it("rejects an invalid pair", [&]() {
+			auto cache = LFU_cache_client(10);
+			AssertThrows(invalid_argument, cache.set_pair(NAN, -122.19));
+			AssertThrows(invalid_argument, cache.set_pair(47.36, -200.0));
+		});
+		it("rejects a query before a pair is set", [&]() {
+			auto start_cache = SAMPLE_DATA_START;
+			auto cache = LFU_cache_client(10);
+			AssertThrows(invalid_argument, cache.query(start_cache, start_cache + 25 * ONE_HOUR));
+			AssertThat(cache.remote_call_count(), Equals(0));
+		});
 	});
 
 });